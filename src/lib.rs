@@ -1,11 +1,19 @@
 use std::{
     collections::HashMap,
-    fs::{File, OpenOptions},
-    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
-    path::Path,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
 };
 
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm, Nonce as AesGcmNonce,
+};
+use argon2::Argon2;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce as ChaChaNonce};
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
 
 pub type ByteString = Vec<u8>;
@@ -17,53 +25,398 @@ pub struct KeyValuePair {
     pub value: ByteString,
 }
 
+/// One entry in a `dump`/`restore` file: a key/value pair as it looks once
+/// serialized to portable JSON, with both fields base64-encoded since the
+/// underlying bytes may not be valid UTF-8 (or even text at all).
+#[derive(Debug, Serialize, Deserialize)]
+struct DumpEntry {
+    key: String,
+    value: String,
+}
+
 const CHECKSUM_CHECKER: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_CKSUM);
 
-/// Store structured data using the Bitcask format
+/// Signature + version written once at the very start of every data file,
+/// the same self-identifying scheme the hint file uses below. The record
+/// header grew a compression codec byte (and, for compressed records, an
+/// original-length field) after this store format was first shipped; a
+/// store written before that change has no such marker, so `open` can tell
+/// the two apart and reject the old one with a clear error instead of
+/// `process_record` misreading the first byte of every key as a codec.
+const DATA_MAGIC: [u8; 4] = [0x89, b'M', b'R', b'D'];
+const DATA_FORMAT_VERSION: u8 = 2;
+const DATA_HEADER_LEN: usize = DATA_MAGIC.len() + 1;
+
+fn write_data_header(f: &mut File) -> io::Result<()> {
+    f.write_all(&DATA_MAGIC)?;
+    f.write_all(&[DATA_FORMAT_VERSION])
+}
+
+/// Validate the header written by `write_data_header`, leaving the file's
+/// cursor positioned right after it. Anything else -- a missing magic, or a
+/// version we don't know how to read -- is rejected outright rather than
+/// risked against `process_record`.
+fn check_data_header(f: &mut File) -> io::Result<()> {
+    let mut header = [0u8; DATA_HEADER_LEN];
+    f.read_exact(&mut header).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a mini-redis data file: missing the format header. Stores created before \
+             per-record compression support was added predate this header; migrate them with \
+             `dump` on the old binary and `restore` here.",
+        )
+    })?;
+
+    if header[..DATA_MAGIC.len()] != DATA_MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a mini-redis data file: missing the format header. Stores created before \
+             per-record compression support was added predate this header; migrate them with \
+             `dump` on the old binary and `restore` here.",
+        ));
+    }
+
+    let version = header[DATA_MAGIC.len()];
+    if version != DATA_FORMAT_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported data-file format version: {}", version),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Signature for the on-disk hint file, mirroring the self-identifying
+/// header PNG uses: a non-ASCII first byte (so the file is never mistaken
+/// for text), followed by the format name and a CR-LF pair so truncated or
+/// line-ending-mangled transfers are caught immediately.
+const HINT_MAGIC: [u8; 8] = [0x89, b'M', b'R', b'H', b'I', b'N', b'\r', b'\n'];
+const HINT_FORMAT_VERSION: u8 = 1;
+
+/// Byte following the magic+version header that says whether the payload
+/// after it is plain bincode or `[nonce][ciphertext]`. The hint file holds
+/// every key in the store in the clear; for an encrypted store that would
+/// leak every key name onto disk right next to the encrypted data file, so
+/// `save_index`/`load_hint` protect it with the same AEAD as the records.
+const HINT_PLAINTEXT: u8 = 0;
+const HINT_ENCRYPTED: u8 = 1;
+
+/// The payload stored in a `<path>.hint` file: the index it describes and
+/// the length of the data file it was computed against. `load` takes the
+/// index as-is when `data_len` still matches the current data file length,
+/// and scans only the records past `data_len` when the data file has grown
+/// since; otherwise it ignores the hint and scans the whole file.
+#[derive(Debug, Serialize, Deserialize)]
+struct HintFile {
+    data_len: u64,
+    index: HashMap<ByteString, u64>,
+}
+
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const KDF_ARGON2: u8 = 1;
+
+/// `[cipher id][kdf id][salt]`, written once right after the data-file
+/// format header of an encrypted store's data file so `open_encrypted` can rederive the key and
+/// pick the right cipher without the caller repeating that choice.
+const ENCRYPTION_HEADER_LEN: usize = 1 + 1 + SALT_LEN;
+
+/// Caller-selectable AEAD for `open_encrypted`, mapped internally to the
+/// header byte `Cipher` identifies itself by on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AeadCipher {
+    Aes256Gcm,
+    ChaCha20Poly1305,
+}
+
+impl AeadCipher {
+    fn id(self) -> u8 {
+        match self {
+            AeadCipher::Aes256Gcm => Cipher::AES_256_GCM_ID,
+            AeadCipher::ChaCha20Poly1305 => Cipher::CHACHA20_POLY1305_ID,
+        }
+    }
+}
+
+/// The AEAD in use for a given store. Selected by a header byte so a store
+/// created with one codec can still be identified (and rejected cleanly,
+/// rather than silently misread) if support for it is ever dropped.
+///
+/// `Aes256Gcm` is boxed: it is roughly 30x the size of `ChaCha20Poly1305`,
+/// and every `MiniRedis` (even an unencrypted one, where `cipher` is always
+/// `None`) carries an `Option<Cipher>` field, so an unboxed variant would
+/// size every store to its largest cipher.
+#[derive(Clone)]
+enum Cipher {
+    Aes256Gcm(Box<Aes256Gcm>),
+    ChaCha20Poly1305(ChaCha20Poly1305),
+}
+
+// Implemented by hand (rather than derived) so the key material the
+// underlying cipher holds can never end up in a `{:?}` log line.
+impl std::fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Cipher::Aes256Gcm(_) => "Aes256Gcm",
+            Cipher::ChaCha20Poly1305(_) => "ChaCha20Poly1305",
+        };
+        f.debug_tuple(name).finish()
+    }
+}
+
+impl Cipher {
+    const AES_256_GCM_ID: u8 = 1;
+    const CHACHA20_POLY1305_ID: u8 = 2;
+
+    fn from_id(id: u8, key: &[u8; KEY_LEN]) -> io::Result<Self> {
+        match id {
+            Self::AES_256_GCM_ID => Ok(Cipher::Aes256Gcm(Box::new(Aes256Gcm::new(key.into())))),
+            Self::CHACHA20_POLY1305_ID => {
+                Ok(Cipher::ChaCha20Poly1305(ChaCha20Poly1305::new(key.into())))
+            }
+            other => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown cipher id: {}", other),
+            )),
+        }
+    }
+
+    fn encrypt(&self, nonce: &[u8; NONCE_LEN], plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        let result = match self {
+            Cipher::Aes256Gcm(c) => c.encrypt(AesGcmNonce::from_slice(nonce), plaintext),
+            Cipher::ChaCha20Poly1305(c) => c.encrypt(ChaChaNonce::from_slice(nonce), plaintext),
+        };
+        result.map_err(|_| io::Error::other("failed to encrypt record"))
+    }
+
+    fn decrypt(&self, nonce: &[u8; NONCE_LEN], ciphertext: &[u8]) -> io::Result<Vec<u8>> {
+        let result = match self {
+            Cipher::Aes256Gcm(c) => c.decrypt(AesGcmNonce::from_slice(nonce), ciphertext),
+            Cipher::ChaCha20Poly1305(c) => c.decrypt(ChaChaNonce::from_slice(nonce), ciphertext),
+        };
+        result.map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "authentication failed: record has been tampered with, or the passphrase is wrong",
+            )
+        })
+    }
+}
+
+/// Codec used to shrink a record's value on disk, stored as a single flag
+/// byte per record so a store can freely mix compressed and uncompressed
+/// records (e.g. once a threshold is introduced on an already-populated
+/// store, or when a value is too small for compression to be worthwhile).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None = 0,
+    Lz4 = 1,
+    Zstd = 2,
+}
+
+impl Compression {
+    fn from_byte(byte: u8) -> io::Result<Self> {
+        match byte {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Lz4),
+            2 => Ok(Compression::Zstd),
+            other => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown compression codec: {}", other),
+            )),
+        }
+    }
+}
+
+fn compress(codec: Compression, data: &[u8]) -> io::Result<ByteString> {
+    match codec {
+        Compression::None => Ok(data.to_vec()),
+        Compression::Lz4 => Ok(lz4_flex::compress(data)),
+        Compression::Zstd => zstd::stream::encode_all(data, 0),
+    }
+}
+
+fn decompress(codec: Compression, data: &[u8], original_len: usize) -> io::Result<ByteString> {
+    match codec {
+        Compression::None => Ok(data.to_vec()),
+        Compression::Lz4 => lz4_flex::decompress(data, original_len)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+        Compression::Zstd => zstd::stream::decode_all(data),
+    }
+}
+
+/// Store structured data using the Bitcask format: an append-only log of
+/// variable-length records, plus an in-memory index mapping each key to the
+/// file offset of its latest record.
 ///
-/// Here is how the layout of an entry looks like:
+/// A plaintext record looks like:
 ///
-///                                                                                                                               
-///                             12 bytes header                                     variable length contents                   
-///                                  |                                                          |                               
-/// |--------------------------------|---------------------------------|  |---------------------|----------------------         
-/// |                                                                  |  |                                           |         
-/// |                                                                  |  |                                           |         
-/// |      Checksum               key length             value length  |  |         key                   value       |         
-/// +------+------+------+ +------+------+------+ +------+------+------+  +--------------------+ +--------------------+         
-/// |      |      |      | |      |      |      | |      |      |      |  |                    | |                    |         
-/// |      |      |      | |      |      |      | |      |      |      |  |                    | |                    |         
-/// +------+------+------+ +------+------+------+ +------+------+------+  +--------------------+ +--------------------+         
-/// |                    | |                    | |                    |  |                    | |                    |         
-/// |----------|---------| |----------|---------| |----------|---------|  |----------|---------| |----------|---------|         
-/// |                      |                      |                       |                      |                   
-///           u32                    u32                    u32              [u8, key length]      [u8, value length]           
+/// `[checksum: u32][key_len: u32][stored_value_len: u32][codec: u8]`
+/// `([original_value_len: u32], only present when codec != 0)`
+/// `[key_len bytes of key][stored_value_len bytes of value]`
+///
+/// `codec` is a `Compression` variant; when it's `None` the value is stored
+/// as-is and `original_value_len` is omitted. `checksum` is a CRC32 over
+/// the key and stored (possibly compressed) value, checked on every read.
+///
+/// An encrypted record (`open_encrypted`) keeps the same `key_len`/
+/// `stored_value_len`/`codec`/`original_value_len` fields, but `checksum` is
+/// unused (left zero) and the key/value bytes are replaced by
+/// `[nonce: 12 bytes][ciphertext: key_len + stored_value_len + 16 bytes]` --
+/// the AEAD tag bundled into the ciphertext authenticates the record
+/// instead of the checksum.
 
 #[derive(Debug)]
 pub struct MiniRedis {
     f: File,
+    path: PathBuf,
+    hint_path: PathBuf,
+    cipher: Option<Cipher>,
+    /// The exact `[cipher id][kdf id][salt]` bytes written after the format
+    /// header of an encrypted data file, kept around so `compact` can stamp the same
+    /// header onto the rewritten file without needing the passphrase again.
+    encryption_header: Option<[u8; ENCRYPTION_HEADER_LEN]>,
+    /// Codec and size threshold set via `with_compression`. Values at or
+    /// under the threshold are stored as-is; compression is never forced
+    /// onto small values where the codec overhead would outweigh the gain.
+    compression: Option<(Compression, usize)>,
     pub index: HashMap<ByteString, u64>,
 }
 
 impl MiniRedis {
     pub fn open(path: &Path) -> io::Result<Self> {
-        let f = OpenOptions::new()
+        let mut f = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
             .append(true)
             .open(path)?;
 
+        if f.metadata()?.len() == 0 {
+            write_data_header(&mut f)?;
+        } else {
+            f.seek(SeekFrom::Start(0))?;
+            check_data_header(&mut f)?;
+        }
+
         let index = HashMap::new();
-        Ok(MiniRedis { f, index })
+        Ok(MiniRedis {
+            f,
+            path: path.to_path_buf(),
+            hint_path: MiniRedis::hint_path_for(path),
+            cipher: None,
+            encryption_header: None,
+            compression: None,
+            index,
+        })
     }
 
-    pub fn load(&mut self) -> io::Result<()> {
+    /// Compress values larger than `threshold` bytes with `codec` before
+    /// they are written. Existing records are unaffected; codec choice and
+    /// threshold only govern records inserted from this point on.
+    pub fn with_compression(mut self, codec: Compression, threshold: usize) -> Self {
+        self.compression = Some((codec, threshold));
+        self
+    }
+
+    /// Open (or create) a store whose data file is encrypted at rest. The
+    /// key is derived from `passphrase` with Argon2 using a random salt
+    /// generated on first creation and persisted in the data file's header;
+    /// reopening the store re-derives the same key from that salt. `cipher`
+    /// picks the AEAD that protects every record; it only matters when
+    /// creating a new store -- reopening an existing one always honors the
+    /// id already stamped in its header, not this argument. The AEAD's
+    /// authentication tag supersedes the plaintext format's CRC32 check.
+    pub fn open_encrypted(path: &Path, passphrase: &str, cipher: AeadCipher) -> io::Result<Self> {
+        let mut f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .append(true)
+            .open(path)?;
+
+        let is_new = f.metadata()?.len() == 0;
+        if is_new {
+            write_data_header(&mut f)?;
+        } else {
+            f.seek(SeekFrom::Start(0))?;
+            check_data_header(&mut f)?;
+        }
+
+        let mut header = [0u8; ENCRYPTION_HEADER_LEN];
+        if is_new {
+            header[0] = cipher.id();
+            header[1] = KDF_ARGON2;
+            rand::thread_rng().fill_bytes(&mut header[2..2 + SALT_LEN]);
+            f.write_all(&header)?;
+        } else {
+            f.read_exact(&mut header)?;
+        }
+
+        let cipher_id = header[0];
+        let kdf_id = header[1];
+        let mut salt = [0u8; SALT_LEN];
+        salt.copy_from_slice(&header[2..2 + SALT_LEN]);
+
+        if kdf_id != KDF_ARGON2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported key-derivation id: {}", kdf_id),
+            ));
+        }
+
+        let mut key = [0u8; KEY_LEN];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("key derivation failed: {}", err),
+                )
+            })?;
+
+        let cipher = Cipher::from_id(cipher_id, &key)?;
+
+        Ok(MiniRedis {
+            f,
+            path: path.to_path_buf(),
+            hint_path: MiniRedis::hint_path_for(path),
+            cipher: Some(cipher),
+            encryption_header: Some(header),
+            compression: None,
+            index: HashMap::new(),
+        })
+    }
+
+    /// Byte offset where the first (or next) record begins: past the
+    /// data-file format header, and past the encryption header too when
+    /// the store is encrypted.
+    fn data_start(&self) -> u64 {
+        let mut start = DATA_HEADER_LEN as u64;
+        if self.cipher.is_some() {
+            start += ENCRYPTION_HEADER_LEN as u64;
+        }
+        start
+    }
+
+    fn hint_path_for(path: &Path) -> PathBuf {
+        let mut hint_path = path.as_os_str().to_owned();
+        hint_path.push(".hint");
+        PathBuf::from(hint_path)
+    }
+
+    /// Scan the data file the slow way, indexing the latest offset for
+    /// every key found at or after `from`.
+    fn scan_from(&mut self, from: u64) -> io::Result<()> {
+        let cipher = self.cipher.as_ref();
         let mut f = BufReader::new(&mut self.f);
+        f.seek(SeekFrom::Start(from))?;
 
         loop {
             let position = f.seek(io::SeekFrom::Current(0))?;
-            let maybe_kv = MiniRedis::process_record(&mut f);
+            let maybe_kv = MiniRedis::process_record(&mut f, cipher);
             let kv = match maybe_kv {
                 Ok(kv) => kv,
                 Err(err) => match err.kind() {
@@ -80,37 +433,187 @@ impl MiniRedis {
         Ok(())
     }
 
-    fn process_record<R: Read>(f: &mut R) -> io::Result<KeyValuePair> {
+    /// Read and validate the hint file, returning its payload if the magic
+    /// header and format version both check out. A missing or corrupt hint
+    /// file is not an error here; callers fall back to scanning the data
+    /// file from scratch in that case.
+    fn load_hint(&self) -> io::Result<Option<HintFile>> {
+        let mut f = match File::open(&self.hint_path) {
+            Ok(f) => f,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        let mut magic = [0u8; 8];
+        if f.read_exact(&mut magic).is_err() || magic != HINT_MAGIC {
+            return Ok(None);
+        }
+
+        let mut version = [0u8; 1];
+        if f.read_exact(&mut version).is_err() || version[0] != HINT_FORMAT_VERSION {
+            return Ok(None);
+        }
+
+        let mut mode = [0u8; 1];
+        if f.read_exact(&mut mode).is_err() {
+            return Ok(None);
+        }
+
+        match (mode[0], self.cipher.as_ref()) {
+            (HINT_PLAINTEXT, None) => match bincode::deserialize_from(f) {
+                Ok(hint) => Ok(Some(hint)),
+                Err(_) => Ok(None),
+            },
+            (HINT_ENCRYPTED, Some(cipher)) => {
+                let mut nonce = [0u8; NONCE_LEN];
+                if f.read_exact(&mut nonce).is_err() {
+                    return Ok(None);
+                }
+                let mut ciphertext = Vec::new();
+                f.read_to_end(&mut ciphertext)?;
+
+                let payload = match cipher.decrypt(&nonce, &ciphertext) {
+                    Ok(payload) => payload,
+                    Err(_) => return Ok(None),
+                };
+                match bincode::deserialize(&payload[..]) {
+                    Ok(hint) => Ok(Some(hint)),
+                    Err(_) => Ok(None),
+                }
+            }
+            // The hint's mode doesn't match how this store was opened (e.g.
+            // a plaintext hint left over from before the store was switched
+            // to `open_encrypted`, or vice versa). Rather than guess, fall
+            // back to a full scan.
+            _ => Ok(None),
+        }
+    }
+
+    /// Persist the current index as a hint file, so the next `load` can
+    /// skip scanning the whole data file. Written atomically via a
+    /// write-then-rename so a crash mid-write never leaves a torn hint
+    /// file behind. When the store is encrypted, the payload is encrypted
+    /// with the same AEAD as the records -- otherwise every key name in the
+    /// store would sit in the clear in this file, right next to the
+    /// encrypted data file it is meant to protect.
+    pub fn save_index(&mut self) -> io::Result<()> {
+        let data_len = self.f.metadata()?.len();
+        let hint = HintFile {
+            data_len,
+            index: self.index.clone(),
+        };
+        let payload = bincode::serialize(&hint).map_err(io::Error::other)?;
+
+        let mut tmp_path = self.hint_path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        let mut tmp = BufWriter::new(File::create(&tmp_path)?);
+        tmp.write_all(&HINT_MAGIC)?;
+        tmp.write_all(&[HINT_FORMAT_VERSION])?;
+
+        match &self.cipher {
+            None => {
+                tmp.write_all(&[HINT_PLAINTEXT])?;
+                tmp.write_all(&payload)?;
+            }
+            Some(cipher) => {
+                let mut nonce = [0u8; NONCE_LEN];
+                rand::thread_rng().fill_bytes(&mut nonce);
+                let ciphertext = cipher.encrypt(&nonce, &payload)?;
+                tmp.write_all(&[HINT_ENCRYPTED])?;
+                tmp.write_all(&nonce)?;
+                tmp.write_all(&ciphertext)?;
+            }
+        }
+
+        tmp.flush()?;
+        drop(tmp);
+
+        fs::rename(&tmp_path, &self.hint_path)
+    }
+
+    pub fn load(&mut self) -> io::Result<()> {
+        let data_len = self.f.metadata()?.len();
+        let header_len = self.data_start();
+
+        if let Some(hint) = self.load_hint()? {
+            if hint.data_len == data_len {
+                self.index = hint.index;
+                return Ok(());
+            }
+
+            if hint.data_len < data_len {
+                self.index = hint.index;
+                return self.scan_from(hint.data_len);
+            }
+        }
+
+        self.index = HashMap::new();
+        self.scan_from(header_len)
+    }
+
+    /// Parse a single record out of `f`. When `cipher` is `Some`, the
+    /// payload is `[nonce][ciphertext+tag]` and the AEAD tag stands in for
+    /// the CRC32 check used by plaintext records; a failed decryption
+    /// surfaces as an `io::Error` rather than panicking, since it may just
+    /// mean the wrong passphrase was supplied.
+    fn process_record<R: Read>(f: &mut R, cipher: Option<&Cipher>) -> io::Result<KeyValuePair> {
         // We need to make sure that the byte order written to disk
         // is consistent across platforms. The byteorder crate helps
         // us to use little endian across all systems.
         let saved_checksum = f.read_u32::<LittleEndian>()?;
         let key_len = f.read_u32::<LittleEndian>()?;
-        let value_len = f.read_u32::<LittleEndian>()?;
-        let data_len = key_len + value_len;
+        let stored_value_len = f.read_u32::<LittleEndian>()?;
+        let codec = Compression::from_byte(f.read_u8()?)?;
+        let original_value_len = if codec == Compression::None {
+            stored_value_len
+        } else {
+            f.read_u32::<LittleEndian>()?
+        };
 
-        let mut data = ByteString::with_capacity(data_len as usize);
+        let mut data = match cipher {
+            None => {
+                let data_len = key_len + stored_value_len;
+                let mut data = ByteString::with_capacity(data_len as usize);
 
-        // read the data payload from the reader
-        // but place it into our buffer so we can split it up later
-        {
-            f.by_ref().take(data_len as u64).read_to_end(&mut data)?;
-        }
+                // read the data payload from the reader
+                // but place it into our buffer so we can split it up later
+                {
+                    f.by_ref().take(data_len as u64).read_to_end(&mut data)?;
+                }
 
-        debug_assert_eq!(data.len(), data_len as usize);
+                debug_assert_eq!(data.len(), data_len as usize);
 
-        // make sure the checksum header matches with the computed checksum
-        // bail otherwise.
-        let checksum = CHECKSUM_CHECKER.checksum(&data);
-        if checksum != saved_checksum {
-            panic!(
-                "data corruption encountered ({:08x} != {:08x})",
-                checksum, saved_checksum
-            );
-        }
+                // make sure the checksum header matches with the computed checksum
+                // (over the stored, possibly-compressed bytes) bail otherwise.
+                let checksum = CHECKSUM_CHECKER.checksum(&data);
+                if checksum != saved_checksum {
+                    panic!(
+                        "data corruption encountered ({:08x} != {:08x})",
+                        checksum, saved_checksum
+                    );
+                }
+
+                data
+            }
+            Some(cipher) => {
+                let mut nonce = [0u8; NONCE_LEN];
+                f.read_exact(&mut nonce)?;
 
-        let value = data.split_off(key_len as usize);
+                let ciphertext_len = key_len as usize + stored_value_len as usize + 16;
+                let mut ciphertext = ByteString::with_capacity(ciphertext_len);
+                f.by_ref()
+                    .take(ciphertext_len as u64)
+                    .read_to_end(&mut ciphertext)?;
+                debug_assert_eq!(ciphertext.len(), ciphertext_len);
+
+                cipher.decrypt(&nonce, &ciphertext)?
+            }
+        };
+
+        let stored_value = data.split_off(key_len as usize);
         let key = data;
+        let value = decompress(codec, &stored_value, original_value_len as usize)?;
 
         Ok(KeyValuePair { key, value })
     }
@@ -130,19 +633,80 @@ impl MiniRedis {
     }
 
     pub fn get_at(&mut self, position: u64) -> io::Result<KeyValuePair> {
+        let cipher = self.cipher.as_ref();
         let mut f = BufReader::new(&mut self.f);
         f.seek(io::SeekFrom::Start(position))?;
-        let kv = MiniRedis::process_record(&mut f)?;
+        let kv = MiniRedis::process_record(&mut f, cipher)?;
         Ok(kv)
     }
 
+    /// Resolve many keys at once. Offsets are looked up in `self.index` and
+    /// sorted ascending first, so the underlying file is read with a single
+    /// forward sweep instead of the random-access seeking a loop of `get`
+    /// calls would do. Keys with no entry in the index are silently
+    /// omitted from the result.
+    pub fn get_many(&mut self, keys: &[&ByteStr]) -> io::Result<HashMap<ByteString, ByteString>> {
+        let mut offsets: Vec<(u64, ByteString)> = keys
+            .iter()
+            .filter_map(|key| {
+                self.index
+                    .get(*key)
+                    .map(|&position| (position, key.to_vec()))
+            })
+            .collect();
+        offsets.sort_unstable_by_key(|(position, _)| *position);
+
+        let cipher = self.cipher.as_ref();
+        let mut f = BufReader::new(&mut self.f);
+        let mut results = HashMap::with_capacity(offsets.len());
+
+        for (position, key) in offsets {
+            f.seek(SeekFrom::Start(position))?;
+            let kv = MiniRedis::process_record(&mut f, cipher)?;
+            results.insert(key, kv.value);
+        }
+
+        Ok(results)
+    }
+
+    /// Stream the store's live entries without loading them all into
+    /// memory at once. Walks `self.index`'s offsets in file order and
+    /// yields the record at each, skipping tombstones (empty values left
+    /// behind by `delete`); since the index only ever tracks the latest
+    /// offset per key, superseded records are never visited.
+    pub fn scan(&mut self) -> impl Iterator<Item = io::Result<KeyValuePair>> + '_ {
+        let mut offsets: Vec<u64> = self.index.values().copied().collect();
+        offsets.sort_unstable();
+        let mut offsets = offsets.into_iter();
+
+        let cipher = self.cipher.clone();
+        let f = &mut self.f;
+
+        std::iter::from_fn(move || loop {
+            let position = offsets.next()?;
+            let mut reader = BufReader::new(&mut *f);
+            if let Err(err) = reader.seek(SeekFrom::Start(position)) {
+                return Some(Err(err));
+            }
+
+            match MiniRedis::process_record(&mut reader, cipher.as_ref()) {
+                Ok(kv) if kv.value.is_empty() => continue,
+                Ok(kv) => return Some(Ok(kv)),
+                Err(err) => return Some(Err(err)),
+            }
+        })
+    }
+
     pub fn find(&mut self, target: &ByteStr) -> io::Result<Option<(u64, ByteString)>> {
+        let cipher = self.cipher.as_ref();
+        let header_len = self.data_start();
         let mut f = BufReader::new(&mut self.f);
+        f.seek(SeekFrom::Start(header_len))?;
         let mut found: Option<(u64, ByteString)> = None;
 
         loop {
             let position = f.seek(io::SeekFrom::Current(0))?;
-            let maybe_kv = MiniRedis::process_record(&mut f);
+            let maybe_kv = MiniRedis::process_record(&mut f, cipher);
             let kv = match maybe_kv {
                 Ok(kv) => kv,
                 Err(err) => match err.kind() {
@@ -164,6 +728,14 @@ impl MiniRedis {
         Ok(found)
     }
 
+    /// Write `key`/`value`, superseding any previous record for `key`. Only
+    /// the in-memory index is updated here; the hint file on disk is left
+    /// as-is, since re-persisting the whole index on every write would make
+    /// each call here O(index size) instead of O(1) amortized. A stale hint
+    /// is still safe to reopen with: `load` detects it fell behind and
+    /// scans just the tail that grew since it was saved. Call `save_index`
+    /// explicitly once a batch of writes is done (e.g. right before a
+    /// short-lived process exits) to make the fast path available again.
     pub fn insert(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
         let position = self.insert_but_ignore_index(key, value)?;
         self.index.insert(key.to_vec(), position);
@@ -172,19 +744,19 @@ impl MiniRedis {
 
     pub fn insert_but_ignore_index(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<u64> {
         let key_len = key.len();
-        let value_len = value.len();
-        let mut tmp = ByteString::with_capacity(key_len + value_len);
 
-        for byte in key {
-            tmp.push(*byte);
-        }
-
-        for byte in value {
-            tmp.push(*byte);
-        }
+        let (codec, stored_value) = match self.compression {
+            Some((codec, threshold)) if codec != Compression::None && value.len() > threshold => {
+                (codec, compress(codec, value)?)
+            }
+            _ => (Compression::None, value.to_vec()),
+        };
 
-        let checksum = CHECKSUM_CHECKER.checksum(&tmp);
+        let mut tmp = ByteString::with_capacity(key_len + stored_value.len());
+        tmp.extend_from_slice(key);
+        tmp.extend_from_slice(&stored_value);
 
+        let cipher = self.cipher.as_ref();
         let mut f = BufWriter::new(&mut self.f);
         let next_byte = SeekFrom::End(0);
         // keep track of the current position in the stream
@@ -193,12 +765,39 @@ impl MiniRedis {
         // Move the needle to the end of the stream so we
         // append the new value to the stream.
         f.seek(next_byte)?;
-        // write the header first
-        f.write_u32::<LittleEndian>(checksum)?;
-        f.write_u32::<LittleEndian>(key_len as u32)?;
-        f.write_u32::<LittleEndian>(value_len as u32)?;
-        // write the content
-        f.write_all(&tmp)?;
+
+        match cipher {
+            None => {
+                let checksum = CHECKSUM_CHECKER.checksum(&tmp);
+                // write the header first
+                f.write_u32::<LittleEndian>(checksum)?;
+                f.write_u32::<LittleEndian>(key_len as u32)?;
+                f.write_u32::<LittleEndian>(stored_value.len() as u32)?;
+                f.write_u8(codec as u8)?;
+                if codec != Compression::None {
+                    f.write_u32::<LittleEndian>(value.len() as u32)?;
+                }
+                // write the content
+                f.write_all(&tmp)?;
+            }
+            Some(cipher) => {
+                let mut nonce = [0u8; NONCE_LEN];
+                rand::thread_rng().fill_bytes(&mut nonce);
+                let ciphertext = cipher.encrypt(&nonce, &tmp)?;
+
+                // The checksum field is unused here: the AEAD tag bundled
+                // into `ciphertext` is what authenticates this record.
+                f.write_u32::<LittleEndian>(0)?;
+                f.write_u32::<LittleEndian>(key_len as u32)?;
+                f.write_u32::<LittleEndian>(stored_value.len() as u32)?;
+                f.write_u8(codec as u8)?;
+                if codec != Compression::None {
+                    f.write_u32::<LittleEndian>(value.len() as u32)?;
+                }
+                f.write_all(&nonce)?;
+                f.write_all(&ciphertext)?;
+            }
+        }
 
         // The caller will use this position to index
         // the key/value pair that were just added.
@@ -214,4 +813,273 @@ impl MiniRedis {
     pub fn delete(&mut self, key: &ByteStr) -> io::Result<()> {
         self.insert(key, b"")
     }
+
+    /// Scan the full data file (ignoring `self.index`) to find the latest
+    /// offset of every key, the same way `load`'s full-scan fallback does.
+    /// Shared by `compact` and `dump` so both agree on what "live" means.
+    fn latest_offsets(&mut self) -> io::Result<HashMap<ByteString, u64>> {
+        let mut latest: HashMap<ByteString, u64> = HashMap::new();
+        let header_len = self.data_start();
+        let cipher = self.cipher.as_ref();
+        let mut f = BufReader::new(&mut self.f);
+        f.seek(SeekFrom::Start(header_len))?;
+
+        loop {
+            let position = f.seek(SeekFrom::Current(0))?;
+            let maybe_kv = MiniRedis::process_record(&mut f, cipher);
+            let kv = match maybe_kv {
+                Ok(kv) => kv,
+                Err(err) => match err.kind() {
+                    io::ErrorKind::UnexpectedEof => break,
+                    _ => return Err(err),
+                },
+            };
+
+            latest.insert(kv.key, position);
+        }
+
+        Ok(latest)
+    }
+
+    /// Rewrite the data file so it only contains the latest live entry per
+    /// key, reclaiming the space taken up by superseded records and
+    /// tombstones left behind by `update`/`delete`.
+    pub fn compact(&mut self) -> io::Result<()> {
+        // Scan the current file the same way `load` does, keeping only the
+        // last-seen offset per key so overwritten entries don't survive
+        // the compaction.
+        let latest = self.latest_offsets()?;
+
+        let compact_path = self.path.with_extension("compact");
+        // A previous `compact()` may have been interrupted after this file
+        // was created but before the final rename below. Start from a clean
+        // file so the offsets recorded in `new_index` line up with what is
+        // actually written, rather than being computed relative to a stale
+        // leftover whose length `insert_but_ignore_index` (append-mode
+        // writes always land at the real EOF) knows nothing about.
+        match fs::remove_file(&compact_path) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        let mut compacted = MiniRedis::open(&compact_path)?;
+        // Carry the encryption over to the rewritten file: stamp the same
+        // header (so the salt, and therefore the derived key, is unchanged)
+        // and reuse the already-derived cipher instead of asking for the
+        // passphrase again.
+        if let (Some(cipher), Some(header)) = (&self.cipher, &self.encryption_header) {
+            compacted.f.write_all(header)?;
+            compacted.cipher = Some(cipher.clone());
+            compacted.encryption_header = Some(*header);
+        }
+        compacted.compression = self.compression;
+
+        let mut new_index = HashMap::with_capacity(latest.len());
+        for (_key, position) in latest {
+            let kv = self.get_at(position)?;
+            // Tombstones (empty values written by `delete`) are dropped
+            // entirely; there is nothing left to reclaim space for.
+            if kv.value.is_empty() {
+                continue;
+            }
+
+            let new_position = compacted.insert_but_ignore_index(&kv.key, &kv.value)?;
+            new_index.insert(kv.key, new_position);
+        }
+
+        // Make sure the rewritten file is flushed to disk before it takes
+        // the place of the original.
+        compacted.f.sync_all()?;
+        drop(compacted);
+
+        fs::rename(&compact_path, &self.path)?;
+
+        self.f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        // `insert_but_ignore_index` records the cursor position as the new
+        // record's offset, so move it to EOF rather than leaving it at 0.
+        self.f.seek(SeekFrom::End(0))?;
+        self.index = new_index;
+        self.save_index()?;
+
+        Ok(())
+    }
+
+    /// Write every live key/value pair to `out` as a portable, version-
+    /// independent backup: one JSON object per line, with both fields
+    /// base64-encoded since values may be arbitrary, non-UTF-8 bytes.
+    /// Tombstones and superseded records are skipped, just like `compact`.
+    pub fn dump(&mut self, out: &mut impl Write) -> io::Result<()> {
+        let latest = self.latest_offsets()?;
+        let mut offsets: Vec<u64> = latest.into_values().collect();
+        offsets.sort_unstable();
+
+        for position in offsets {
+            let kv = self.get_at(position)?;
+            if kv.value.is_empty() {
+                continue;
+            }
+
+            let entry = DumpEntry {
+                key: BASE64.encode(&kv.key),
+                value: BASE64.encode(&kv.value),
+            };
+            serde_json::to_writer(&mut *out, &entry).map_err(io::Error::other)?;
+            out.write_all(b"\n")?;
+        }
+
+        Ok(())
+    }
+
+    /// Rebuild the store from a file written by `dump`, inserting each
+    /// entry in order so the index is built up as it goes. Intended for an
+    /// empty store; existing entries are not cleared first.
+    pub fn restore(&mut self, input: &mut impl Read) -> io::Result<()> {
+        for line in BufReader::new(input).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let entry: DumpEntry = serde_json::from_str(&line)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            let key = BASE64
+                .decode(&entry.key)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            let value = BASE64
+                .decode(&entry.value)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+            self.insert(&key, &value)?;
+        }
+
+        // One save at the end, rather than one per `insert` above, keeps a
+        // restore of N entries O(n) instead of O(n^2).
+        self.save_index()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+
+    /// A fresh, collision-free path under the system temp dir. There is no
+    /// `tempfile` dependency here, so uniqueness is rolled by hand from the
+    /// pid plus a per-process counter.
+    fn temp_path(name: &str) -> PathBuf {
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        std::env::temp_dir().join(format!(
+            "mini-redis-test-{}-{}-{}.db",
+            std::process::id(),
+            n,
+            name
+        ))
+    }
+
+    fn cleanup(path: &Path) {
+        let _ = fs::remove_file(path);
+        let _ = fs::remove_file(MiniRedis::hint_path_for(path));
+    }
+
+    #[test]
+    fn round_trip_insert_and_reopen() {
+        let path = temp_path("round-trip");
+
+        let mut store = MiniRedis::open(&path).unwrap();
+        store.load().unwrap();
+        store.insert(b"name", b"chashu").unwrap();
+        store.insert(b"other", b"nori").unwrap();
+        store.update(b"name", b"chashu-cat").unwrap();
+        store.save_index().unwrap();
+        drop(store);
+
+        let mut reopened = MiniRedis::open(&path).unwrap();
+        reopened.load().unwrap();
+        assert_eq!(reopened.get(b"name").unwrap(), Some(b"chashu-cat".to_vec()));
+        assert_eq!(reopened.get(b"other").unwrap(), Some(b"nori".to_vec()));
+
+        cleanup(&path);
+    }
+
+    #[test]
+    fn reopen_after_compact_keeps_latest_values() {
+        let path = temp_path("compact");
+
+        let mut store = MiniRedis::open(&path).unwrap();
+        store.load().unwrap();
+        store.insert(b"name", b"chashu").unwrap();
+        store.update(b"name", b"nori").unwrap();
+        store.insert(b"other", b"stray").unwrap();
+        store.delete(b"other").unwrap();
+        store.compact().unwrap();
+        drop(store);
+
+        let mut reopened = MiniRedis::open(&path).unwrap();
+        reopened.load().unwrap();
+        assert_eq!(reopened.get(b"name").unwrap(), Some(b"nori".to_vec()));
+        assert_eq!(reopened.get(b"other").unwrap(), None);
+
+        cleanup(&path);
+    }
+
+    #[test]
+    fn wrong_passphrase_fails_to_decrypt() {
+        let path = temp_path("wrong-pass");
+
+        let mut store =
+            MiniRedis::open_encrypted(&path, "correct horse", AeadCipher::Aes256Gcm).unwrap();
+        store.insert(b"name", b"chashu").unwrap();
+        drop(store);
+
+        let mut reopened =
+            MiniRedis::open_encrypted(&path, "wrong horse", AeadCipher::Aes256Gcm).unwrap();
+        let err = reopened.load().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        cleanup(&path);
+    }
+
+    #[test]
+    fn hint_file_does_not_leak_keys_for_encrypted_stores() {
+        let path = temp_path("hint-leak");
+        let key = b"alice-ssn-123-45-6789";
+
+        let mut store =
+            MiniRedis::open_encrypted(&path, "hunter2", AeadCipher::ChaCha20Poly1305).unwrap();
+        store.insert(key, b"top secret").unwrap();
+        store.save_index().unwrap();
+        drop(store);
+
+        let hint_bytes = fs::read(MiniRedis::hint_path_for(&path)).unwrap();
+        assert!(!hint_bytes
+            .windows(key.len())
+            .any(|window| window == key.as_slice()));
+
+        cleanup(&path);
+    }
+
+    #[test]
+    fn compression_round_trips_above_threshold() {
+        let path = temp_path("compression");
+        let value = vec![b'x'; 64];
+
+        let mut store = MiniRedis::open(&path)
+            .unwrap()
+            .with_compression(Compression::Zstd, 16);
+        store.load().unwrap();
+        store.insert(b"blob", &value).unwrap();
+        store.insert(b"small", b"hi").unwrap();
+
+        assert_eq!(store.get(b"blob").unwrap(), Some(value));
+        assert_eq!(store.get(b"small").unwrap(), Some(b"hi".to_vec()));
+
+        cleanup(&path);
+    }
 }
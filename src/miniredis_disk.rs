@@ -1,5 +1,5 @@
-use lib_miniredis::{ByteStr, ByteString, MiniRedis};
-use std::collections::HashMap;
+use lib_miniredis::MiniRedis;
+use std::fs::File;
 
 #[cfg(target_os = "windows")]
 const USAGE: &str = "
@@ -8,6 +8,8 @@ Usage:
   miniredis.exe FILE delete KEY
   miniredis.exe FILE insert KEY VALUE
   miniredis.exe FILE update KEY VALUE
+  miniredis.exe FILE dump OUT_FILE
+  miniredis.exe FILE restore SRC_FILE
 ";
 
 #[cfg(not(target_os = "windows"))]
@@ -17,64 +19,88 @@ Usage:
   miniredis FILE delete KEY
   miniredis FILE insert KEY VALUE
   miniredis FILE update KEY VALUE
+  miniredis FILE dump OUT_FILE
+  miniredis FILE restore SRC_FILE
 ";
 
-//
-fn store_index_on_disk(store: &mut MiniRedis, index_key: &ByteStr) {
-    store.index.remove(index_key);
-    let index_as_bytes = bincode::serialize(&store.index).unwrap();
-    store.index = HashMap::new();
-    store.insert(index_key, &index_as_bytes).unwrap();
-}
-
 fn main() {
-    const INDEX_KEY: &ByteStr = b"+index";
-
     let args: Vec<String> = std::env::args().collect();
     let filename = args.get(1).expect(&USAGE);
     let action = args.get(2).expect(&USAGE).as_ref();
-    let key = args.get(3).expect(&USAGE).as_ref();
+    let operand = args.get(3).expect(&USAGE);
+    let key = operand.as_ref();
     let maybe_value = args.get(4);
 
     let path = std::path::Path::new(&filename);
     let mut store = MiniRedis::open(path).expect("Could not open the given file.");
 
-    // @TODO: There is still a problem here:
-    // When we call `load`, it will rebuild the index from scratch, which defeats
-    // the purpose of having the already persisted index on disk
+    // `load` picks up the `<path>.hint` file when it is still valid for the
+    // current data file, so reopening a large store doesn't require
+    // rescanning every record.
     store
         .load()
         .expect("Could not load data from the given file.");
 
     match action {
-        "get" => {
-            let index_as_bytes = store.get(&INDEX_KEY).unwrap().unwrap();
-            let decoded_index = bincode::deserialize(&index_as_bytes);
-            let index: HashMap<ByteString, u64> = decoded_index.unwrap();
-
-            match index.get(key) {
-                None => eprintln!("Key not found. Key={:?}", key),
-                Some(&i) => {
-                    let pair = store.get_at(i).unwrap();
-                    // Values can potentially be just bytes, with no encoding attached.
-                    // so we use the Debug trait to print the value.
-                    println!("{:?}", pair.value);
-                }
+        "get" => match store.get(key).unwrap() {
+            None => eprintln!("Key not found. Key={:?}", key),
+            Some(value) => {
+                // Values can potentially be just bytes, with no encoding attached.
+                // so we use the Debug trait to print the value.
+                println!("{:?}", value);
             }
-        }
+        },
 
-        "delete" => store.delete(key).unwrap(),
+        "delete" => {
+            store.delete(key).unwrap();
+            // This process exits right after the command runs, so the hint
+            // file must be persisted here rather than left for some later
+            // caller to flush.
+            store
+                .save_index()
+                .expect("Could not persist the hint file.");
+        }
 
         "insert" => {
             let value = maybe_value.expect(&USAGE).as_ref();
             store.insert(key, value).unwrap();
-            store_index_on_disk(&mut store, &INDEX_KEY);
+            store
+                .save_index()
+                .expect("Could not persist the hint file.");
         }
 
         "update" => {
             let value = maybe_value.expect(&USAGE).as_ref();
             store.update(key, value).unwrap();
-            store_index_on_disk(&mut store, &INDEX_KEY);
+            store
+                .save_index()
+                .expect("Could not persist the hint file.");
+        }
+
+        "dump" => {
+            let mut out = File::create(operand).expect("Could not create the dump file.");
+            store.dump(&mut out).expect("Could not dump the store.");
+        }
+
+        "restore" => {
+            // `restore` inserts on top of whatever the target already has;
+            // without this check it would silently merge into an existing
+            // store instead of rebuilding the clean, equivalent copy a
+            // migration expects.
+            if !store.index.is_empty() {
+                eprintln!(
+                    "Refusing to restore into {:?}: it already has entries. \
+                     Restore into a fresh (non-existent or empty) file so the \
+                     result matches the dump, instead of merging into what's there.",
+                    path
+                );
+                std::process::exit(1);
+            }
+
+            let mut src = File::open(operand).expect("Could not open the dump file.");
+            store
+                .restore(&mut src)
+                .expect("Could not restore from the dump file.");
         }
 
         _ => eprintln!("{}", &USAGE),
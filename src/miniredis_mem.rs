@@ -1,4 +1,5 @@
 use lib_miniredis::MiniRedis;
+use std::fs::File;
 
 #[cfg(target_os = "windows")]
 const USAGE: &str = "
@@ -7,6 +8,8 @@ const USAGE: &str = "
     miniredis_mem.exe FILE delete KEY
     miniredis_mem.exe FILE insert KEY VALUE
     miniredis_mem.exe FILE update KEY VALUE
+    miniredis_mem.exe FILE dump OUT_FILE
+    miniredis_mem.exe FILE restore SRC_FILE
 ";
 
 #[cfg(not(target_os = "windows"))]
@@ -16,13 +19,16 @@ const USAGE: &str = "
     miniredis_mem FILE delete KEY
     miniredis_mem FILE insert KEY VALUE
     miniredis_mem FILE update KEY VALUE
+    miniredis_mem FILE dump OUT_FILE
+    miniredis_mem FILE restore SRC_FILE
 ";
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
     let filename = args.get(1).expect(&USAGE);
     let action = args.get(2).expect(&USAGE).as_ref();
-    let key = args.get(3).expect(&USAGE).as_ref();
+    let operand = args.get(3).expect(&USAGE);
+    let key = operand.as_ref();
     let maybe_value = args.get(4);
 
     let path = std::path::Path::new(&filename);
@@ -36,16 +42,56 @@ fn main() {
             Some(value) => println!("{:?}", value),
         },
 
-        "delete" => store.delete(key).unwrap(),
+        "delete" => {
+            store.delete(key).unwrap();
+            // This process exits right after the command runs, so the hint
+            // file must be persisted here rather than left for some later
+            // caller to flush.
+            store
+                .save_index()
+                .expect("Could not persist the hint file.");
+        }
 
         "insert" => {
             let value = maybe_value.expect(&USAGE).as_ref();
             store.insert(key, value).unwrap();
+            store
+                .save_index()
+                .expect("Could not persist the hint file.");
         }
 
         "update" => {
             let value = maybe_value.expect(&USAGE).as_ref();
             store.update(key, value).unwrap();
+            store
+                .save_index()
+                .expect("Could not persist the hint file.");
+        }
+
+        "dump" => {
+            let mut out = File::create(operand).expect("Could not create the dump file.");
+            store.dump(&mut out).expect("Could not dump the store.");
+        }
+
+        "restore" => {
+            // `restore` inserts on top of whatever the target already has;
+            // without this check it would silently merge into an existing
+            // store instead of rebuilding the clean, equivalent copy a
+            // migration expects.
+            if !store.index.is_empty() {
+                eprintln!(
+                    "Refusing to restore into {:?}: it already has entries. \
+                     Restore into a fresh (non-existent or empty) file so the \
+                     result matches the dump, instead of merging into what's there.",
+                    path
+                );
+                std::process::exit(1);
+            }
+
+            let mut src = File::open(operand).expect("Could not open the dump file.");
+            store
+                .restore(&mut src)
+                .expect("Could not restore from the dump file.");
         }
 
         _ => eprint!("{}", &USAGE),